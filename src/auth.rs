@@ -0,0 +1,87 @@
+//! Client-certificate based identity: fingerprinting, a pluggable trust store, and an
+//! in-memory session map keyed by fingerprint. Used to gate routes registered with
+//! [`Builder::add_protected_route`](crate::Builder::add_protected_route).
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+use sha2::{Digest, Sha256};
+use tokio_rustls::rustls::Certificate;
+
+/// A stable identifier for a client certificate: the SHA-256 digest of its DER encoding.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub(crate) fn of(cert: &Certificate) -> Self {
+        let digest = Sha256::digest(&cert.0);
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&digest);
+
+        Self(bytes)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The outcome of checking a client certificate against a [`TrustStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trust {
+    /// The certificate is known and currently valid.
+    Authorized,
+    /// The certificate isn't recognized.
+    Unauthorized,
+    /// The certificate is recognized, but has expired.
+    Expired,
+}
+
+/// A pluggable store that decides whether a client certificate is allowed to access a
+/// protected route, keyed by its [`Fingerprint`].
+///
+/// A bare closure of type `Fn(&Fingerprint) -> Trust` implements this trait, so simple
+/// allow-lists don't need a dedicated type. Without a configured trust store, protected routes
+/// operate TOFU-style: any presented certificate is authorized, and its fingerprint is
+/// remembered for the session map.
+pub trait TrustStore: Send + Sync {
+    /// Check whether `fingerprint` is trusted.
+    fn check(&self, fingerprint: &Fingerprint) -> Trust;
+}
+
+impl<F: Fn(&Fingerprint) -> Trust + Send + Sync> TrustStore for F {
+    fn check(&self, fingerprint: &Fingerprint) -> Trust {
+        self(fingerprint)
+    }
+}
+
+/// Per-client state associated with a returning client certificate.
+pub type Session = HashMap<String, String>;
+
+/// An in-memory map from client certificate [`Fingerprint`] to [`Session`].
+#[derive(Default)]
+pub(crate) struct SessionStore {
+    sessions: Mutex<HashMap<Fingerprint, Arc<Mutex<Session>>>>,
+}
+
+impl SessionStore {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the session for `fingerprint`, creating an empty one if none exists yet.
+    pub(crate) fn get_or_create(&self, fingerprint: &Fingerprint) -> Arc<Mutex<Session>> {
+        self.sessions.lock().unwrap()
+            .entry(fingerprint.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(Session::new())))
+            .clone()
+    }
+}
@@ -0,0 +1,159 @@
+//! An outbound Gemini client, for making requests from within a handler (proxies, link
+//! checkers, aggregators, and the like).
+
+use std::{
+    convert::TryFrom,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use anyhow::*;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufStream, ReadBuf},
+    net::TcpStream,
+    time::{timeout, Instant, Sleep},
+};
+use tokio_rustls::{rustls, TlsConnector};
+use uriparse::URIReference;
+
+use crate::{
+    types::{Body, Response, Status},
+    GEMINI_PORT,
+};
+
+/// A client for making outbound `gemini://` requests.
+#[derive(Clone)]
+pub struct Client {
+    connector: TlsConnector,
+    timeout: Duration,
+}
+
+impl Client {
+    /// Create a client that trusts the Mozilla root CA list and presents no client certificate.
+    ///
+    /// To present a client certificate, build a [`rustls::ClientConfig`] yourself and convert
+    /// it with [`Client::from`].
+    pub fn new() -> Self {
+        let mut config = rustls::ClientConfig::new();
+        config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+
+        config.into()
+    }
+
+    /// Set the timeout for connecting to the server and exchanging the request and response,
+    /// including streaming the body.
+    ///
+    /// Connecting, sending the request and reading the status line must finish within this
+    /// timeout in total. The body is bounded per read instead: reading it fails once no data
+    /// arrives for longer than this timeout, however long the whole transfer takes.
+    ///
+    /// The default timeout is 30 seconds.
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Make a request for `url`, which must be an absolute `gemini://` URL.
+    pub async fn request(&self, url: &str) -> Result<Response> {
+        timeout(self.timeout, self.request_inner(url))
+            .await
+            .context("Timed out while making request")?
+    }
+
+    async fn request_inner(&self, url: &str) -> Result<Response> {
+        let uri = URIReference::try_from(url)
+            .context("URL is invalid")?;
+
+        let scheme_is_gemini = uri.scheme()
+            .map(|scheme| scheme.as_str().eq_ignore_ascii_case("gemini"))
+            .unwrap_or(false);
+        ensure!(scheme_is_gemini, "URL scheme must be `gemini`");
+
+        let authority = uri.authority()
+            .ok_or_else(|| anyhow!("URL is missing a host"))?;
+        ensure!(authority.username().is_none(), "URL must not contain userinfo");
+
+        let host = authority.host().to_string();
+        let port = authority.port().unwrap_or(GEMINI_PORT);
+
+        let stream = TcpStream::connect((host.as_str(), port)).await
+            .with_context(|| format!("Failed to connect to `{}:{}`", host, port))?;
+
+        let name = webpki::DNSNameRef::try_from_ascii_str(&host)
+            .with_context(|| format!("`{}` is not a valid DNS name", host))?;
+        let stream = self.connector.connect(name, stream).await
+            .context("Failed to establish TLS session")?;
+        let mut stream = BufStream::new(stream);
+
+        stream.write_all(format!("{}\r\n", url).as_bytes()).await
+            .context("Failed to send request")?;
+        stream.flush().await
+            .context("Failed to send request")?;
+
+        let mut line = Vec::new();
+        stream.read_until(b'\n', &mut line).await
+            .context("Failed to read response status line")?;
+        ensure!(line.ends_with(b"\r\n"), "Response status line not terminated with CRLF");
+        line.truncate(line.len() - 2);
+
+        let line = String::from_utf8(line)
+            .context("Response status line is not valid UTF-8")?;
+        let (code, meta) = line.split_once(' ')
+            .ok_or_else(|| anyhow!("Response status line is malformed"))?;
+        let code = code.parse()
+            .context("Response status code is not a two-digit number")?;
+        let status = Status::from_code(code)?;
+
+        let body = TimeoutReader::new(stream, self.timeout);
+
+        Ok(Response::new(status, meta, Some(Body::Reader(Box::new(body)))))
+    }
+}
+
+/// Wraps an [`AsyncRead`], failing the read with a timeout error if no progress is made for
+/// longer than `timeout` between bytes.
+///
+/// This lets [`Client::set_timeout`] bound body transfer as well as connecting and exchanging
+/// the status line, which `request_inner` can't do on its own since it hands the body back to
+/// the caller as a live stream rather than reading it to completion itself.
+struct TimeoutReader<R> {
+    inner: R,
+    timeout: Duration,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl<R> TimeoutReader<R> {
+    fn new(inner: R, timeout: Duration) -> Self {
+        Self { inner, timeout, deadline: Box::pin(tokio::time::sleep(timeout)) }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TimeoutReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                self.deadline.as_mut().reset(Instant::now() + self.timeout);
+                Poll::Ready(result)
+            },
+            Poll::Pending => {
+                if self.deadline.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out while reading response body")));
+                }
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl From<rustls::ClientConfig> for Client {
+    /// Build a client from a custom TLS configuration, e.g. one presenting a client certificate.
+    fn from(config: rustls::ClientConfig) -> Self {
+        Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
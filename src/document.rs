@@ -0,0 +1,250 @@
+//! Structured `text/gemini` documents: a builder for generating them, and a parser for reading
+//! them back.
+
+use std::fmt;
+use std::str::FromStr;
+use anyhow::*;
+
+/// The level of a [`Node::Heading`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadingLevel {
+    H1,
+    H2,
+    H3,
+}
+
+/// A single line (or preformatted block) of a [`Document`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    /// A `#`/`##`/`###` heading.
+    Heading(HeadingLevel, String),
+    /// A ```` ``` ```` delimited preformatted block, with optional alt text on the opening
+    /// fence.
+    Preformatted { alt: Option<String>, text: String },
+    /// A `=> url [label]` link line.
+    Link { url: String, label: Option<String> },
+    /// A `* ` list item.
+    ListItem(String),
+    /// A `> ` quote line.
+    Quote(String),
+    /// Any other line, rendered as-is (including blank lines).
+    Text(String),
+}
+
+/// A structured `text/gemini` document.
+///
+/// Build one with [`Document::new`] and the `add_*` methods, or read one with
+/// [`Document::parse`]. [`Document`] implements [`ToString`] via [`fmt::Display`], rendering
+/// back to `text/gemini`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Document {
+    nodes: Vec<Node>,
+}
+
+impl Document {
+    /// Create an empty document.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The document's nodes, in order, for programmatic inspection or transformation (e.g.
+    /// rewriting relative links when proxying).
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// The document's nodes, mutably, for programmatic transformation.
+    pub fn nodes_mut(&mut self) -> &mut [Node] {
+        &mut self.nodes
+    }
+
+    /// Append a heading.
+    pub fn add_heading(&mut self, level: HeadingLevel, text: impl Into<String>) -> &mut Self {
+        self.nodes.push(Node::Heading(level, text.into()));
+        self
+    }
+
+    /// Append a line of plain text.
+    pub fn add_text(&mut self, text: impl Into<String>) -> &mut Self {
+        self.nodes.push(Node::Text(text.into()));
+        self
+    }
+
+    /// Append a blank line.
+    pub fn add_blank_line(&mut self) -> &mut Self {
+        self.add_text("")
+    }
+
+    /// Append a link with a label.
+    pub fn add_link(&mut self, url: impl Into<String>, label: impl Into<String>) -> &mut Self {
+        self.nodes.push(Node::Link { url: url.into(), label: Some(label.into()) });
+        self
+    }
+
+    /// Append a link with no label.
+    pub fn add_link_without_label(&mut self, url: impl Into<String>) -> &mut Self {
+        self.nodes.push(Node::Link { url: url.into(), label: None });
+        self
+    }
+
+    /// Append a list item.
+    pub fn add_list_item(&mut self, text: impl Into<String>) -> &mut Self {
+        self.nodes.push(Node::ListItem(text.into()));
+        self
+    }
+
+    /// Append a quoted line.
+    pub fn add_quote(&mut self, text: impl Into<String>) -> &mut Self {
+        self.nodes.push(Node::Quote(text.into()));
+        self
+    }
+
+    /// Append a preformatted block with no alt text.
+    pub fn add_preformatted(&mut self, text: impl Into<String>) -> &mut Self {
+        self.nodes.push(Node::Preformatted { alt: None, text: text.into() });
+        self
+    }
+
+    /// Append a preformatted block with alt text on its opening fence.
+    pub fn add_preformatted_with_alt(&mut self, alt: impl Into<String>, text: impl Into<String>) -> &mut Self {
+        self.nodes.push(Node::Preformatted { alt: Some(alt.into()), text: text.into() });
+        self
+    }
+
+    /// Parse a `text/gemini` document.
+    ///
+    /// Round-trips with [`Document::to_string`] for canonical input: headings, links, list
+    /// items, quotes and preformatted blocks are recognized by their gemtext marker, and
+    /// everything else (including blank lines) is kept as plain text.
+    pub fn parse(text: &str) -> Result<Self> {
+        text.parse()
+    }
+}
+
+impl FromStr for Document {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let mut nodes = Vec::new();
+        let mut preformatted: Option<(Option<String>, Vec<&str>)> = None;
+
+        if text.is_empty() {
+            return Ok(Self { nodes });
+        }
+
+        let text = text.strip_suffix('\n').unwrap_or(text);
+
+        for line in text.split('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+
+            if preformatted.is_some() && line == "```" {
+                let (alt, buffer) = preformatted.take().unwrap();
+                nodes.push(Node::Preformatted { alt, text: buffer.join("\n") });
+                continue;
+            }
+
+            if let Some((_, buffer)) = preformatted.as_mut() {
+                buffer.push(line);
+                continue;
+            }
+
+            if let Some(alt) = line.strip_prefix("```") {
+                let alt = if alt.is_empty() { None } else { Some(alt.to_string()) };
+                preformatted = Some((alt, Vec::new()));
+            } else if let Some(text) = line.strip_prefix("### ") {
+                nodes.push(Node::Heading(HeadingLevel::H3, text.to_string()));
+            } else if let Some(text) = line.strip_prefix("## ") {
+                nodes.push(Node::Heading(HeadingLevel::H2, text.to_string()));
+            } else if let Some(text) = line.strip_prefix("# ") {
+                nodes.push(Node::Heading(HeadingLevel::H1, text.to_string()));
+            } else if let Some(rest) = line.strip_prefix("=> ") {
+                let (url, label) = match rest.split_once(char::is_whitespace) {
+                    Some((url, label)) => (url, Some(label.trim_start().to_string())),
+                    None => (rest, None),
+                };
+                nodes.push(Node::Link { url: url.to_string(), label });
+            } else if let Some(text) = line.strip_prefix("* ") {
+                nodes.push(Node::ListItem(text.to_string()));
+            } else if let Some(text) = line.strip_prefix("> ") {
+                nodes.push(Node::Quote(text.to_string()));
+            } else {
+                nodes.push(Node::Text(line.to_string()));
+            }
+        }
+
+        if let Some((alt, buffer)) = preformatted {
+            nodes.push(Node::Preformatted { alt, text: buffer.join("\n") });
+        }
+
+        Ok(Self { nodes })
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for node in &self.nodes {
+            match node {
+                Node::Heading(HeadingLevel::H1, text) => writeln!(f, "# {}", text)?,
+                Node::Heading(HeadingLevel::H2, text) => writeln!(f, "## {}", text)?,
+                Node::Heading(HeadingLevel::H3, text) => writeln!(f, "### {}", text)?,
+                Node::Preformatted { alt: Some(alt), text } => {
+                    writeln!(f, "```{}", alt)?;
+                    if !text.is_empty() {
+                        writeln!(f, "{}", text)?;
+                    }
+                    writeln!(f, "```")?;
+                },
+                Node::Preformatted { alt: None, text } => {
+                    writeln!(f, "```")?;
+                    if !text.is_empty() {
+                        writeln!(f, "{}", text)?;
+                    }
+                    writeln!(f, "```")?;
+                },
+                Node::Link { url, label: Some(label) } => writeln!(f, "=> {} {}", url, label)?,
+                Node::Link { url, label: None } => writeln!(f, "=> {}", url)?,
+                Node::ListItem(text) => writeln!(f, "* {}", text)?,
+                Node::Quote(text) => writeln!(f, "> {}", text)?,
+                Node::Text(text) => writeln!(f, "{}", text)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_round_trips_to_string() {
+        let gemtext = "\
+# Heading 1
+## Heading 2
+### Heading 3
+
+Some plain text
+=> gemini://example.com/ An example link
+=> gemini://example.com/bare
+* A list item
+> A quote
+```alt text
+preformatted
+  line two
+```
+";
+
+        let doc = Document::parse(gemtext).unwrap();
+        assert_eq!(doc.to_string(), gemtext);
+    }
+
+    #[test]
+    fn parse_round_trips_empty_preformatted_block() {
+        let gemtext = "```\n```\n";
+
+        let doc = Document::parse(gemtext).unwrap();
+        assert_eq!(doc.nodes(), &[Node::Preformatted { alt: None, text: String::new() }]);
+        assert_eq!(doc.to_string(), gemtext);
+    }
+}
@@ -1,9 +1,11 @@
 #[macro_use] extern crate log;
 
 use std::{
+    collections::HashMap,
     panic::AssertUnwindSafe,
     convert::TryFrom,
     io::BufReader,
+    path::Path,
     sync::Arc,
     time::Duration,
 };
@@ -16,29 +18,55 @@ use tokio::{
 };
 use tokio::net::TcpListener;
 use rustls::ClientCertVerifier;
+use rustls::sign::CertifiedKey;
 use tokio_rustls::{rustls, TlsAcceptor};
 use rustls::*;
 use anyhow::*;
 use lazy_static::lazy_static;
 
+pub mod auth;
+pub mod client;
+pub mod document;
+pub mod middleware;
 pub mod types;
 pub mod util;
 
 pub use mime;
 pub use uriparse as uri;
+pub use auth::{Fingerprint, Trust, TrustStore};
+pub use client::Client;
+pub use document::Document;
+pub use middleware::{CachingLayer, Layer, RateLimitLayer};
 pub use types::*;
 
+use auth::SessionStore;
+
 pub const REQUEST_URI_MAX_LEN: usize = 1024;
 pub const GEMINI_PORT: u16 = 1965;
 
-type Handler = Arc<dyn Fn(Request) -> HandlerResponse + Send + Sync>;
-pub (crate) type HandlerResponse = BoxFuture<'static, Result<Response>>;
+/// A request handler, as registered with [`Builder::serve`], [`Builder::add_route`] or
+/// [`Builder::add_protected_route`], or produced by wrapping one in a [`Layer`].
+pub type Handler = Arc<dyn Fn(Request) -> HandlerResponse + Send + Sync>;
+/// The future returned by a [`Handler`].
+pub type HandlerResponse = BoxFuture<'static, Result<Response>>;
+
+/// A handler registered for a path prefix, optionally gated behind a client certificate.
+/// See [`Builder::add_route`] and [`Builder::add_protected_route`].
+#[derive(Clone)]
+struct Route {
+    segments: Vec<String>,
+    protected: bool,
+    handler: Handler,
+}
 
 #[derive(Clone)]
 pub struct Server {
     tls_acceptor: TlsAcceptor,
     listener: Arc<TcpListener>,
     handler: Handler,
+    routes: Arc<Vec<Route>>,
+    trust_store: Option<Arc<dyn TrustStore>>,
+    sessions: Arc<SessionStore>,
     timeout: Duration,
 }
 
@@ -90,7 +118,7 @@ impl Server {
 
         request.set_cert(client_cert);
 
-        let handler = (self.handler)(request);
+        let handler = self.dispatch(request);
         let handler = AssertUnwindSafe(handler);
 
         let response = util::HandlerCatchUnwind::new(handler).await
@@ -116,16 +144,129 @@ impl Server {
 
         Ok(())
     }
+
+    /// Find the route matching `request`'s path, if any, and invoke it, denying access to
+    /// protected routes that don't present an authorized client certificate.
+    fn dispatch(&self, mut request: Request) -> HandlerResponse {
+        let path_segments = request.path_segments();
+
+        let route = self.routes.iter()
+            .filter(|route| path_segments.starts_with(&route.segments))
+            .max_by_key(|route| route.segments.len());
+
+        let route = match route {
+            Some(route) => route,
+            None => return (self.handler)(request),
+        };
+
+        request.set_trailing_segments(path_segments[route.segments.len()..].to_vec());
+
+        if !route.protected {
+            return (route.handler)(request);
+        }
+
+        let cert = match request.cert() {
+            Some(cert) => cert.clone(),
+            None => return deny(Status::CLIENT_CERTIFICATE_REQUIRED, "Client certificate required"),
+        };
+
+        let fingerprint = Fingerprint::of(&cert);
+        let trust = self.trust_store.as_deref()
+            .map(|store| store.check(&fingerprint))
+            .unwrap_or(Trust::Authorized);
+
+        match trust {
+            Trust::Unauthorized => deny(Status::CERTIFICATE_NOT_AUTHORIZED, "Certificate not authorized"),
+            Trust::Expired => deny(Status::CERTIFICATE_NOT_VALID, "Certificate expired"),
+            Trust::Authorized => {
+                request.set_session(self.sessions.get_or_create(&fingerprint));
+
+                (route.handler)(request)
+            },
+        }
+    }
+}
+
+/// Immediately respond with `status`/`meta`, without invoking any handler.
+fn deny(status: Status, meta: impl Into<String>) -> HandlerResponse {
+    Box::pin(async move { Ok(Response::new(status, meta, None)) })
 }
 
 pub struct Builder<A> {
     addr: A,
     timeout: Duration,
+    hosts: HashMap<String, Arc<CertifiedKey>>,
+    routes: Vec<Route>,
+    trust_store: Option<Arc<dyn TrustStore>>,
+    layers: Vec<Arc<dyn Layer>>,
 }
 
 impl<A: ToSocketAddrs> Builder<A> {
     fn bind(addr: A) -> Self {
-        Self { addr, timeout: Duration::from_secs(30) }
+        Self {
+            addr,
+            timeout: Duration::from_secs(30),
+            hosts: HashMap::new(),
+            routes: Vec::new(),
+            trust_store: None,
+            layers: Vec::new(),
+        }
+    }
+
+    /// Stack `layer` around every handler this server runs.
+    ///
+    /// Layers wrap in the order they're added: the first layer added is the outermost, seeing
+    /// the request before (and the response after) every layer added subsequently.
+    pub fn with(mut self, layer: impl Layer + 'static) -> Self {
+        self.layers.push(Arc::new(layer));
+        self
+    }
+
+    /// Register `handler` to serve requests whose path starts with `path`.
+    pub fn add_route(mut self, path: &str, handler: impl Fn(Request) -> HandlerResponse + Send + Sync + 'static) -> Self {
+        self.routes.push(Route { segments: path_segments(path), protected: false, handler: Arc::new(handler) });
+        self
+    }
+
+    /// Register `handler` to serve requests whose path starts with `path`, requiring clients
+    /// to present a client certificate.
+    ///
+    /// Requests without a client certificate are rejected with status `60`. If a
+    /// [`TrustStore`] has been configured with [`Builder::set_trust_store`], it's consulted to
+    /// reject requests with status `61` (unauthorized) or `62` (expired); otherwise any
+    /// presented certificate is accepted, TOFU-style. Authorized requests have a
+    /// [`Request::session`] attached, keyed by the certificate's fingerprint.
+    pub fn add_protected_route(mut self, path: &str, handler: impl Fn(Request) -> HandlerResponse + Send + Sync + 'static) -> Self {
+        self.routes.push(Route { segments: path_segments(path), protected: true, handler: Arc::new(handler) });
+        self
+    }
+
+    /// Configure the [`TrustStore`] used to authorize requests to protected routes.
+    pub fn set_trust_store(mut self, trust_store: impl TrustStore + 'static) -> Self {
+        self.trust_store = Some(Arc::new(trust_store));
+        self
+    }
+
+    /// Serve `name` using the certificate chain and key at `cert_path`/`key_path`.
+    ///
+    /// Call this once per hostname to host several Gemini capsules from a single `Server`,
+    /// each identified by the SNI name the client requests during the TLS handshake. Clients
+    /// that don't request one of the configured hostnames (including clients that don't send
+    /// SNI at all) fall back to the certificate at `cert/cert.pem` and `cert/key.pem`.
+    pub fn add_host(
+        mut self,
+        name: impl Into<String>,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let name = name.into();
+        let key = load_certified_key(cert_path.as_ref(), key_path.as_ref())
+            .with_context(|| format!("Failed to load TLS certificate for `{}`", name))?;
+
+        // SNI/DNS hostnames are case-insensitive, so normalize before storing.
+        self.hosts.insert(name.to_lowercase(), Arc::new(key));
+
+        Ok(self)
     }
 
     /// Set the timeout on incoming requests
@@ -149,16 +290,28 @@ impl<A: ToSocketAddrs> Builder<A> {
     where
         F: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
     {
-        let config = tls_config()
+        let config = tls_config(self.hosts)
             .context("Failed to create TLS config")?;
 
         let listener = TcpListener::bind(self.addr).await
             .context("Failed to create socket")?;
 
+        let apply_layers = |handler: Handler| -> Handler {
+            self.layers.iter().rev()
+                .fold(handler, |handler, layer| layer.wrap(handler))
+        };
+
+        let routes = self.routes.into_iter()
+            .map(|route| Route { handler: apply_layers(route.handler), ..route })
+            .collect();
+
         let server = Server {
             tls_acceptor: TlsAcceptor::from(config),
             listener: Arc::new(listener),
-            handler: Arc::new(handler),
+            handler: apply_layers(Arc::new(handler)),
+            routes: Arc::new(routes),
+            trust_store: self.trust_store,
+            sessions: Arc::new(SessionStore::new()),
             timeout: self.timeout,
         };
 
@@ -166,6 +319,14 @@ impl<A: ToSocketAddrs> Builder<A> {
     }
 }
 
+fn path_segments(path: &str) -> Vec<String> {
+    path.trim_matches('/')
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
 async fn receive_request(stream: &mut (impl AsyncBufRead + Unpin)) -> Result<Request> {
     let limit = REQUEST_URI_MAX_LEN + "\r\n".len();
     let mut stream = stream.take(limit as u64);
@@ -227,37 +388,42 @@ async fn send_response_body(body: Body, stream: &mut (impl AsyncWrite + Unpin))
     Ok(())
 }
 
-fn tls_config() -> Result<Arc<ServerConfig>> {
+fn tls_config(hosts: HashMap<String, Arc<CertifiedKey>>) -> Result<Arc<ServerConfig>> {
     let mut config = ServerConfig::new(AllowAnonOrSelfsignedClient::new());
 
-    let cert_chain = load_cert_chain()
-        .context("Failed to load TLS certificate")?;
-    let key = load_key()
-        .context("Failed to load TLS key")?;
-    config.set_single_cert(cert_chain, key)
-        .context("Failed to use loaded TLS certificate")?;
+    let default = load_certified_key(Path::new("cert/cert.pem"), Path::new("cert/key.pem"))
+        .context("Failed to load default TLS certificate")?;
+
+    config.cert_resolver = Arc::new(HostResolver { hosts, default: Arc::new(default) });
 
     Ok(config.into())
 }
 
-fn load_cert_chain() -> Result<Vec<Certificate>> {
-    let cert_path = "cert/cert.pem";
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey> {
+    let cert_chain = load_cert_chain(cert_path)?;
+    let key = load_key(key_path)?;
+    let key = rustls::sign::any_supported_type(&key)
+        .map_err(|_| anyhow!("Unsupported private key `{}`", key_path.display()))?;
+
+    Ok(CertifiedKey::new(cert_chain, Arc::new(key)))
+}
+
+fn load_cert_chain(cert_path: &Path) -> Result<Vec<Certificate>> {
     let certs = std::fs::File::open(cert_path)
-        .with_context(|| format!("Failed to open `{}`", cert_path))?;
+        .with_context(|| format!("Failed to open `{}`", cert_path.display()))?;
     let mut certs = BufReader::new(certs);
     let certs = rustls::internal::pemfile::certs(&mut certs)
-        .map_err(|_| anyhow!("failed to load certs `{}`", cert_path))?;
+        .map_err(|_| anyhow!("failed to load certs `{}`", cert_path.display()))?;
 
     Ok(certs)
 }
 
-fn load_key() -> Result<PrivateKey> {
-    let key_path = "cert/key.pem";
+fn load_key(key_path: &Path) -> Result<PrivateKey> {
     let keys = std::fs::File::open(key_path)
-        .with_context(|| format!("Failed to open `{}`", key_path))?;
+        .with_context(|| format!("Failed to open `{}`", key_path.display()))?;
     let mut keys = BufReader::new(keys);
     let mut keys = rustls::internal::pemfile::pkcs8_private_keys(&mut keys)
-        .map_err(|_| anyhow!("failed to load key `{}`", key_path))?;
+        .map_err(|_| anyhow!("failed to load key `{}`", key_path.display()))?;
 
     ensure!(!keys.is_empty(), "no key found");
 
@@ -266,6 +432,24 @@ fn load_key() -> Result<PrivateKey> {
     Ok(key)
 }
 
+/// Resolves the certificate to present during the TLS handshake based on the SNI name the
+/// client requests, allowing a single [`Server`] to host several hostnames, each with its own
+/// certificate. See [`Builder::add_host`].
+struct HostResolver {
+    hosts: HashMap<String, Arc<CertifiedKey>>,
+    default: Arc<CertifiedKey>,
+}
+
+impl ResolvesServerCert for HostResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+        let key = client_hello.server_name()
+            .and_then(|name| self.hosts.get(&AsRef::<str>::as_ref(&name).to_lowercase()))
+            .unwrap_or(&self.default);
+
+        Some((**key).clone())
+    }
+}
+
 /// Mime for Gemini documents
 pub const GEMINI_MIME_STR: &str = "text/gemini";
 
@@ -318,9 +502,98 @@ impl ClientCertVerifier for AllowAnonOrSelfsignedClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Mutex;
+    use crate::uri::URIReference;
+
+    async fn test_server(routes: Vec<Route>, trust_store: Option<Arc<dyn TrustStore>>) -> Server {
+        let tls_acceptor = TlsAcceptor::from(Arc::new(ServerConfig::new(AllowAnonOrSelfsignedClient::new())));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        Server {
+            tls_acceptor,
+            listener: Arc::new(listener),
+            handler: Arc::new(|_| Box::pin(async { Ok(Response::new(Status::SUCCESS, "default", None)) })),
+            routes: Arc::new(routes),
+            trust_store,
+            sessions: Arc::new(SessionStore::new()),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    fn route(path: &str, protected: bool, handler: Handler) -> Route {
+        Route { segments: path_segments(path), protected, handler }
+    }
+
+    fn request_with_cert(uri: &str, cert: Option<Certificate>) -> Request {
+        let mut request = Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap();
+        request.set_cert(cert);
+        request
+    }
+
+    fn test_cert(byte: u8) -> Certificate {
+        Certificate(vec![byte; 16])
+    }
+
+    fn trust_store(trust: Trust) -> Arc<dyn TrustStore> {
+        Arc::new(move |_: &Fingerprint| trust)
+    }
+
+    async fn status_of(response: HandlerResponse) -> u8 {
+        response.await.unwrap().header().status.code()
+    }
 
     #[test]
     fn gemini_mime_parses() {
         let _: &Mime = &GEMINI_MIME;
     }
+
+    #[tokio::test]
+    async fn unprotected_route_ignores_untrusted_cert() {
+        let handler: Handler = Arc::new(|_| Box::pin(async { Ok(Response::new(Status::SUCCESS, "open", None)) }));
+        let server = test_server(vec![route("/open", false, handler)], Some(trust_store(Trust::Unauthorized))).await;
+
+        let response = server.dispatch(request_with_cert("gemini://example.com/open", Some(test_cert(1))));
+        assert_eq!(status_of(response).await, Status::SUCCESS.code());
+    }
+
+    #[tokio::test]
+    async fn protected_route_without_cert_requires_one() {
+        let handler: Handler = Arc::new(|_| Box::pin(async { Ok(Response::new(Status::SUCCESS, "secret", None)) }));
+        let server = test_server(vec![route("/secret", true, handler)], None).await;
+
+        let response = server.dispatch(request_with_cert("gemini://example.com/secret", None));
+        assert_eq!(status_of(response).await, Status::CLIENT_CERTIFICATE_REQUIRED.code());
+    }
+
+    #[tokio::test]
+    async fn protected_route_honors_trust_store_verdicts() {
+        let handler: Handler = Arc::new(|_| Box::pin(async { Ok(Response::new(Status::SUCCESS, "secret", None)) }));
+        let server = test_server(vec![route("/secret", true, handler.clone())], Some(trust_store(Trust::Unauthorized))).await;
+        let response = server.dispatch(request_with_cert("gemini://example.com/secret", Some(test_cert(1))));
+        assert_eq!(status_of(response).await, Status::CERTIFICATE_NOT_AUTHORIZED.code());
+
+        let server = test_server(vec![route("/secret", true, handler)], Some(trust_store(Trust::Expired))).await;
+        let response = server.dispatch(request_with_cert("gemini://example.com/secret", Some(test_cert(1))));
+        assert_eq!(status_of(response).await, Status::CERTIFICATE_NOT_VALID.code());
+    }
+
+    #[tokio::test]
+    async fn same_fingerprint_shares_a_session() {
+        let seen: Arc<Mutex<Vec<Arc<Mutex<crate::auth::Session>>>>> = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        let handler: Handler = Arc::new(move |request: Request| {
+            let recorded = recorded.clone();
+            Box::pin(async move {
+                recorded.lock().unwrap().push(request.session().unwrap().clone());
+                Ok(Response::new(Status::SUCCESS, "secret", None))
+            })
+        });
+        let server = test_server(vec![route("/secret", true, handler)], None).await;
+
+        server.dispatch(request_with_cert("gemini://example.com/secret", Some(test_cert(7)))).await.unwrap();
+        server.dispatch(request_with_cert("gemini://example.com/secret", Some(test_cert(7)))).await.unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert!(Arc::ptr_eq(&seen[0], &seen[1]));
+    }
 }
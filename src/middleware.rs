@@ -0,0 +1,232 @@
+//! A tower-style middleware stack for layering cross-cutting behavior (logging, rate
+//! limiting, caching, ...) around a [`Handler`]. See [`Builder::with`](crate::Builder::with).
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    types::{Body, Status},
+    Fingerprint, Handler, HandlerResponse, Request, Response,
+};
+
+/// A single layer of middleware, wrapping a [`Handler`] to produce another one.
+pub trait Layer: Send + Sync {
+    /// Wrap `next`, returning a handler that runs this layer's behavior around it.
+    fn wrap(&self, next: Handler) -> Handler;
+}
+
+impl<F: Fn(Handler) -> Handler + Send + Sync> Layer for F {
+    fn wrap(&self, next: Handler) -> Handler {
+        self(next)
+    }
+}
+
+/// Caches responses by request URI and client certificate fingerprint for a fixed
+/// time-to-live.
+///
+/// The fingerprint (or its absence) is part of the cache key so a response rendered for one
+/// client's session on a protected route is never handed out to a different client, or to an
+/// anonymous one, requesting the same URI. Only responses with an in-memory body are cached;
+/// streamed responses pass through uncached, since their body can't be read twice.
+pub struct CachingLayer {
+    ttl: Duration,
+    cache: Arc<Mutex<HashMap<(Option<Fingerprint>, String), (Instant, Status, String, Vec<u8>)>>>,
+}
+
+impl CachingLayer {
+    /// Cache responses for `ttl` before re-running the handler.
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, cache: Arc::new(Mutex::new(HashMap::new())) }
+    }
+}
+
+impl Layer for CachingLayer {
+    fn wrap(&self, next: Handler) -> Handler {
+        let ttl = self.ttl;
+        let cache = self.cache.clone();
+
+        Arc::new(move |request: Request| -> HandlerResponse {
+            let cache = cache.clone();
+            let next = next.clone();
+            let key = (request.cert_fingerprint(), request.uri().to_string());
+
+            Box::pin(async move {
+                if let Some((cached_at, status, meta, body)) = cache.lock().unwrap().get(&key).cloned() {
+                    if cached_at.elapsed() < ttl {
+                        return Ok(Response::new(status, meta, Some(Body::Bytes(body))));
+                    }
+                }
+
+                let mut response = next(request).await?;
+                let header = response.header();
+                let status = header.status;
+                let meta = header.meta.clone();
+
+                if matches!(response.body(), Some(Body::Bytes(_))) {
+                    let body = match response.take_body() {
+                        Some(Body::Bytes(body)) => body,
+                        _ => unreachable!(),
+                    };
+                    cache.lock().unwrap().insert(key, (Instant::now(), status, meta.clone(), body.clone()));
+                    return Ok(Response::new(status, meta, Some(Body::Bytes(body))));
+                }
+
+                Ok(response)
+            })
+        })
+    }
+}
+
+/// Limits clients to a fixed number of requests per time window.
+///
+/// Clients are identified by their client certificate's [`Fingerprint`]; clients that present
+/// no certificate all share a single anonymous bucket.
+pub struct RateLimitLayer {
+    max_requests: usize,
+    window: Duration,
+    buckets: Arc<Mutex<HashMap<Option<Fingerprint>, (Instant, usize)>>>,
+}
+
+impl RateLimitLayer {
+    /// Allow at most `max_requests` requests from each peer per `window`.
+    pub fn new(max_requests: usize, window: Duration) -> Self {
+        Self { max_requests, window, buckets: Arc::new(Mutex::new(HashMap::new())) }
+    }
+}
+
+impl Layer for RateLimitLayer {
+    fn wrap(&self, next: Handler) -> Handler {
+        let max_requests = self.max_requests;
+        let window = self.window;
+        let buckets = self.buckets.clone();
+
+        Arc::new(move |request: Request| -> HandlerResponse {
+            let peer = request.cert_fingerprint();
+            let mut guard = buckets.lock().unwrap();
+            let (started_at, count) = guard.entry(peer).or_insert_with(|| (Instant::now(), 0));
+
+            if started_at.elapsed() > window {
+                *started_at = Instant::now();
+                *count = 0;
+            }
+            *count += 1;
+            let limited = *count > max_requests;
+            drop(guard);
+
+            if limited {
+                return Box::pin(async { Ok(Response::new(Status::TEMPORARY_FAILURE, "Rate limit exceeded", None)) });
+            }
+
+            next(request)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use crate::uri::URIReference;
+
+    fn request(uri: &str) -> Request {
+        Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap()
+    }
+
+    fn request_with_cert(uri: &str, cert_byte: u8) -> Request {
+        let mut request = request(uri);
+        request.set_cert(Some(tokio_rustls::rustls::Certificate(vec![cert_byte; 16])));
+        request
+    }
+
+    #[tokio::test]
+    async fn caching_layer_preserves_streamed_body() {
+        let layer = CachingLayer::new(Duration::from_secs(60));
+        let handler: Handler = Arc::new(|_request| {
+            Box::pin(async {
+                let reader = std::io::Cursor::new(b"streamed body".to_vec());
+                Ok(Response::new(Status::SUCCESS, "text/plain", Some(Body::Reader(Box::new(reader)))))
+            })
+        });
+
+        let wrapped = layer.wrap(handler);
+        let mut response = wrapped(request("gemini://example.com/")).await.unwrap();
+
+        let body = match response.take_body() {
+            Some(Body::Reader(mut reader)) => {
+                let mut buf = Vec::new();
+                tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut buf).await.unwrap();
+                buf
+            },
+            _ => panic!("expected a streamed body"),
+        };
+
+        assert_eq!(body, b"streamed body");
+    }
+
+    #[tokio::test]
+    async fn caching_layer_caches_bytes_bodies() {
+        let layer = CachingLayer::new(Duration::from_secs(60));
+        let calls = Arc::new(Mutex::new(0));
+        let handler: Handler = {
+            let calls = calls.clone();
+            Arc::new(move |_request| {
+                let calls = calls.clone();
+                Box::pin(async move {
+                    *calls.lock().unwrap() += 1;
+                    Ok(Response::new(Status::SUCCESS, "text/plain", Some(Body::Bytes(b"cached".to_vec()))))
+                })
+            })
+        };
+
+        let wrapped = layer.wrap(handler);
+        wrapped(request("gemini://example.com/")).await.unwrap();
+        wrapped(request("gemini://example.com/")).await.unwrap();
+
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn caching_layer_does_not_leak_across_client_certificates() {
+        let layer = CachingLayer::new(Duration::from_secs(60));
+        let calls = Arc::new(Mutex::new(0));
+        let handler: Handler = {
+            let calls = calls.clone();
+            Arc::new(move |_request| {
+                let calls = calls.clone();
+                Box::pin(async move {
+                    let n = {
+                        let mut calls = calls.lock().unwrap();
+                        *calls += 1;
+                        *calls
+                    };
+                    Ok(Response::new(Status::SUCCESS, "text/plain", Some(Body::Bytes(format!("response {}", n).into_bytes()))))
+                })
+            })
+        };
+        let wrapped = layer.wrap(handler);
+
+        async fn body_of(response: &mut Response) -> Vec<u8> {
+            match response.take_body() {
+                Some(Body::Bytes(body)) => body,
+                _ => panic!("expected an in-memory body"),
+            }
+        }
+
+        let mut client_a_first = wrapped(request_with_cert("gemini://example.com/", 1)).await.unwrap();
+        let mut client_a_second = wrapped(request_with_cert("gemini://example.com/", 1)).await.unwrap();
+        let mut client_b = wrapped(request_with_cert("gemini://example.com/", 2)).await.unwrap();
+
+        let client_a_first = body_of(&mut client_a_first).await;
+        let client_a_second = body_of(&mut client_a_second).await;
+        let client_b = body_of(&mut client_b).await;
+
+        // The same client certificate gets its cached response back...
+        assert_eq!(client_a_first, client_a_second);
+        // ...but a different client certificate requesting the same URI never receives it.
+        assert_ne!(client_a_first, client_b);
+        assert_eq!(*calls.lock().unwrap(), 2);
+    }
+}
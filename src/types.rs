@@ -0,0 +1,272 @@
+//! Core request and response types exchanged between a [`Server`](crate::Server) (or
+//! [`Client`](crate::Client)) and its peer.
+
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use anyhow::*;
+use tokio_rustls::rustls;
+use uriparse::URIReference;
+
+use crate::auth::{Fingerprint, Session};
+
+/// An incoming Gemini request.
+#[derive(Debug, Clone)]
+pub struct Request {
+    uri: URIReference<'static>,
+    cert: Option<rustls::Certificate>,
+    trailing: Vec<String>,
+    session: Option<Arc<Mutex<Session>>>,
+}
+
+impl Request {
+    pub(crate) fn from_uri(uri: URIReference<'static>) -> Result<Self> {
+        Ok(Self { uri, cert: None, trailing: Vec::new(), session: None })
+    }
+
+    /// The URI the client requested.
+    pub fn uri(&self) -> &URIReference<'static> {
+        &self.uri
+    }
+
+    /// The non-empty segments of the request's path.
+    pub fn path_segments(&self) -> Vec<String> {
+        self.uri.path()
+            .segments()
+            .map(|segment| segment.as_str().to_string())
+            .filter(|segment| !segment.is_empty())
+            .collect()
+    }
+
+    pub(crate) fn set_trailing_segments(&mut self, trailing: Vec<String>) {
+        self.trailing = trailing;
+    }
+
+    /// The segments of the request's path that come after the route it was dispatched to.
+    pub fn trailing_segments(&self) -> &[String] {
+        &self.trailing
+    }
+
+    pub(crate) fn set_cert(&mut self, cert: Option<rustls::Certificate>) {
+        self.cert = cert;
+    }
+
+    /// The client certificate presented during the TLS handshake, if any.
+    pub fn cert(&self) -> Option<&rustls::Certificate> {
+        self.cert.as_ref()
+    }
+
+    /// A stable fingerprint of the client certificate presented during the TLS handshake,
+    /// if any. See [`crate::auth`].
+    pub fn cert_fingerprint(&self) -> Option<Fingerprint> {
+        self.cert.as_ref().map(Fingerprint::of)
+    }
+
+    pub(crate) fn set_session(&mut self, session: Arc<Mutex<Session>>) {
+        self.session = Some(session);
+    }
+
+    /// The session associated with the client's certificate, if the route this request was
+    /// dispatched to is protected (see [`crate::Builder::add_protected_route`]).
+    pub fn session(&self) -> Option<&Arc<Mutex<Session>>> {
+        self.session.as_ref()
+    }
+
+    /// The request URI's query component, percent-decoded, with `+` also decoded as a space.
+    ///
+    /// This is how a handler reads the user's reply to a prompt issued with
+    /// [`Response::input`] or [`Response::sensitive_input`] on a previous request.
+    pub fn query(&self) -> Option<String> {
+        let query = self.uri.query()?.as_str();
+
+        Some(percent_decode(query))
+    }
+}
+
+/// Decode a percent-encoded string, e.g. a URI query component. `+` is decoded as a space.
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let byte = bytes[i];
+        match byte {
+            b'%' => {
+                let digits = bytes.get(i + 1).zip(bytes.get(i + 2))
+                    .and_then(|(hi, lo)| Some(((*hi as char).to_digit(16)?, (*lo as char).to_digit(16)?)));
+
+                match digits {
+                    // Only consume the escape once it's confirmed valid, so a malformed or
+                    // truncated `%` escape passes its bytes through literally instead of
+                    // eating them.
+                    Some((hi, lo)) => {
+                        decoded.push((hi * 16 + lo) as u8);
+                        i += 3;
+                    },
+                    None => {
+                        decoded.push(byte);
+                        i += 1;
+                    },
+                }
+            },
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            },
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::percent_decode;
+
+    #[test]
+    fn percent_decode_valid_escapes() {
+        assert_eq!(percent_decode("a%20b"), "a b");
+        assert_eq!(percent_decode("a+b"), "a b");
+        assert_eq!(percent_decode("a%2fb"), "a/b");
+    }
+
+    #[test]
+    fn percent_decode_passes_through_malformed_escapes() {
+        // A bare `%` with no following digit at all.
+        assert_eq!(percent_decode("a%"), "a%");
+        // A `%` followed by only one byte, which isn't a valid escape.
+        assert_eq!(percent_decode("a%b"), "a%b");
+        // A `%` followed by a non-hex digit.
+        assert_eq!(percent_decode("a%2zc"), "a%2zc");
+    }
+}
+
+/// A two-digit Gemini response status code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status(u8);
+
+impl Status {
+    /// `10`: the client should prompt the user for input and retry with it as the query.
+    pub const INPUT: Status = Status(10);
+    /// `11`: like [`Status::INPUT`], but the client shouldn't echo or store the reply.
+    pub const SENSITIVE_INPUT: Status = Status(11);
+    /// `20`: the request succeeded and the body follows.
+    pub const SUCCESS: Status = Status(20);
+    /// `30`: the client should request the given URI instead, without remembering to do so
+    /// in the future.
+    pub const REDIRECT_TEMPORARY: Status = Status(30);
+    /// `31`: the client should request the given URI instead, remembering it as the
+    /// resource's new permanent location.
+    pub const REDIRECT_PERMANENT: Status = Status(31);
+    /// `40`: the request failed for a temporary reason.
+    pub const TEMPORARY_FAILURE: Status = Status(40);
+    /// `50`: the request failed for a permanent reason, e.g. a bug in the handler.
+    pub const PERMANENT_FAILURE: Status = Status(50);
+    /// `60`: the route requires a client certificate, and none was presented.
+    pub const CLIENT_CERTIFICATE_REQUIRED: Status = Status(60);
+    /// `61`: the presented client certificate is not authorized for this route.
+    pub const CERTIFICATE_NOT_AUTHORIZED: Status = Status(61);
+    /// `62`: the presented client certificate is recognized, but is no longer valid.
+    pub const CERTIFICATE_NOT_VALID: Status = Status(62);
+
+    /// Wrap a raw status code.
+    ///
+    /// Returns an error if `code` is outside the `10..=69` range reserved for Gemini statuses.
+    pub fn from_code(code: u8) -> Result<Self> {
+        ensure!((10..=69).contains(&code), "status code `{}` is out of range", code);
+        Ok(Status(code))
+    }
+
+    /// The raw two-digit status code.
+    pub fn code(&self) -> u8 {
+        self.0
+    }
+}
+
+/// The status line sent ahead of a [`Response`]'s body.
+pub struct ResponseHeader {
+    pub(crate) status: Status,
+    pub(crate) meta: String,
+}
+
+/// The body of a [`Response`].
+pub enum Body {
+    /// A body that is already fully in memory.
+    Bytes(Vec<u8>),
+    /// A body streamed from an async reader as it's produced.
+    Reader(Box<dyn tokio::io::AsyncRead + Send + Unpin>),
+}
+
+/// A response to a [`Request`].
+pub struct Response {
+    header: ResponseHeader,
+    body: Option<Body>,
+}
+
+impl Response {
+    pub(crate) fn new(status: Status, meta: impl Into<String>, body: Option<Body>) -> Self {
+        Self {
+            header: ResponseHeader { status, meta: meta.into() },
+            body,
+        }
+    }
+
+    /// Respond with a rendered `text/gemini` document.
+    pub fn document(document: crate::document::Document) -> Self {
+        Self::new(Status::SUCCESS, crate::GEMINI_MIME_STR, Some(Body::Bytes(document.to_string().into_bytes())))
+    }
+
+    /// Respond with a permanent failure, used when request handling itself fails.
+    pub fn server_error(message: impl Into<String>) -> Result<Self> {
+        Ok(Self::new(Status::PERMANENT_FAILURE, message, None))
+    }
+
+    /// Prompt the user for input; their reply is available via [`Request::query`] when they
+    /// retry the request.
+    pub fn input(prompt: impl Into<String>) -> Self {
+        Self::new(Status::INPUT, prompt, None)
+    }
+
+    /// Prompt the user for sensitive input, e.g. a password, which the client shouldn't echo
+    /// or remember in its history.
+    pub fn sensitive_input(prompt: impl Into<String>) -> Self {
+        Self::new(Status::SENSITIVE_INPUT, prompt, None)
+    }
+
+    /// Redirect the client to `uri`, without asking it to remember the redirect.
+    pub fn redirect_temporary(uri: impl AsRef<str>) -> Result<Self> {
+        Self::redirect(Status::REDIRECT_TEMPORARY, uri.as_ref())
+    }
+
+    /// Redirect the client to `uri`, asking it to remember this as the resource's new
+    /// permanent location.
+    pub fn redirect_permanent(uri: impl AsRef<str>) -> Result<Self> {
+        Self::redirect(Status::REDIRECT_PERMANENT, uri.as_ref())
+    }
+
+    fn redirect(status: Status, uri: &str) -> Result<Self> {
+        URIReference::try_from(uri)
+            .with_context(|| format!("`{}` is not a valid URI", uri))?;
+
+        Ok(Self::new(status, uri, None))
+    }
+
+    /// The response's status line.
+    pub fn header(&self) -> &ResponseHeader {
+        &self.header
+    }
+
+    /// Take the response body, leaving `None` in its place.
+    pub fn take_body(&mut self) -> Option<Body> {
+        self.body.take()
+    }
+
+    /// The response body, without consuming it.
+    pub(crate) fn body(&self) -> Option<&Body> {
+        self.body.as_ref()
+    }
+}